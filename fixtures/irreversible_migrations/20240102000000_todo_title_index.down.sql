@@ -0,0 +1,2 @@
+-- forgot to drop todos_title_idx
+SELECT 1;
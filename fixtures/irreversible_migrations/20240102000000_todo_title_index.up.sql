@@ -0,0 +1 @@
+CREATE INDEX todos_title_idx ON todos (title);
@@ -0,0 +1,17 @@
+CREATE TYPE priority AS ENUM ('low', 'medium', 'high');
+
+CREATE TABLE users(
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(64) NOT NULL,
+    email VARCHAR(128) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE tickets(
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    title VARCHAR(255) NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('open', 'closed')),
+    priority priority NOT NULL,
+    estimate SMALLINT
+);
@@ -0,0 +1,12 @@
+CREATE TABLE documents(
+    id SERIAL PRIMARY KEY,
+    tenant_id INTEGER NOT NULL,
+    title TEXT NOT NULL
+);
+
+ALTER TABLE documents ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY tenant_isolation ON documents
+    USING (tenant_id = current_setting('app.tenant_id', true)::integer);
+
+INSERT INTO documents(tenant_id, title) VALUES (1, 'one'), (1, 'uno'), (2, 'two');
@@ -0,0 +1,5 @@
+CREATE TABLE todos(
+    id SERIAL PRIMARY KEY,
+    title VARCHAR(255) NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'open'
+);
@@ -0,0 +1,3 @@
+ALTER TABLE todos ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'open';
+UPDATE todos SET status = CASE WHEN completed THEN 'done' ELSE 'open' END;
+ALTER TABLE todos DROP COLUMN completed;
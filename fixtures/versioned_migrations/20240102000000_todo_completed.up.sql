@@ -0,0 +1,3 @@
+ALTER TABLE todos ADD COLUMN completed BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE todos SET completed = (status = 'done');
+ALTER TABLE todos DROP COLUMN status;
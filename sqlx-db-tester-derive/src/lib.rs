@@ -0,0 +1,238 @@
+use heck::{ToKebabCase, ToLowerCamelCase, ToShoutySnakeCase, ToSnakeCase, ToUpperCamelCase};
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    Data, DeriveInput, Expr, Fields, LitStr, Token, ext::IdentExt, meta::ParseNestedMeta,
+    parse_macro_input, token,
+};
+
+/// Derive a fixture builder for a row struct.
+///
+/// The struct names its table with `#[factory(table = "...")]`. `Todo::factory()`
+/// returns a `TodoFactory` with a setter per field, and `create(&tdb)` inserts the
+/// row and reads it back through `sqlx::FromRow`. Field types must implement
+/// `serde::Serialize`.
+///
+/// Fields that aren't set get a default: `#[factory(default = expr)]`, where `n` is
+/// the row's sequence number, or else `Fake::fake`, which only fills columns the
+/// database has no default for. Required foreign keys that aren't set point to
+/// parent rows created with generated values. Fields that aren't columns are
+/// marked `#[factory(skip)]`. Columns are named like `sqlx::FromRow` names them,
+/// following `#[sqlx(rename = "...")]`, `#[sqlx(rename_all = "...")]` and
+/// `#[sqlx(skip)]`; `#[sqlx(flatten)]` isn't supported.
+#[proc_macro_derive(Factory, attributes(factory))]
+pub fn derive_factory(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Field {
+    ident: syn::Ident,
+    column: String,
+    ty: syn::Type,
+    default: Option<Expr>,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut table = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("factory")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `table = \"...\"`"))
+            }
+        })?;
+    }
+    let mut rename_all = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("sqlx")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                rename_all = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                skip_value(&meta)
+            }
+        })?;
+    }
+    let table = table.ok_or_else(|| {
+        syn::Error::new_spanned(&input.ident, "missing #[factory(table = \"...\")]")
+    })?;
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "Factory can't be derived for generic structs",
+        ));
+    }
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Factory can only be derived for structs",
+        ));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Factory requires named fields",
+        ));
+    };
+
+    let mut fields = vec![];
+    for field in &named.named {
+        let mut skip = false;
+        let mut default = None;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("factory")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else if meta.path.is_ident("default") {
+                    default = Some(meta.value()?.parse::<Expr>()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `skip` or `default = ...`"))
+                }
+            })?;
+        }
+        let ident = field.ident.clone().expect("named field");
+        let mut column = None;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("sqlx")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    column = Some(meta.value()?.parse::<LitStr>()?.value());
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else if meta.path.is_ident("flatten") {
+                    Err(meta.error("Factory doesn't support #[sqlx(flatten)]"))
+                } else {
+                    skip_value(&meta)
+                }
+            })?;
+        }
+        let column = match (column, &rename_all) {
+            (Some(column), _) => column,
+            (None, Some(case)) => rename(&ident.unraw().to_string(), case)?,
+            (None, None) => ident.unraw().to_string(),
+        };
+        if !skip {
+            fields.push(Field {
+                ident,
+                column,
+                ty: field.ty.clone(),
+                default,
+            });
+        }
+    }
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let factory = format_ident!("{name}Factory");
+    let idents: Vec<_> = fields.iter().map(|f| &f.ident).collect();
+    let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    let private = quote!(::sqlx_db_tester::__private);
+    let collect = fields.iter().map(|f| {
+        let ident = &f.ident;
+        let ty = &f.ty;
+        let column = &f.column;
+        let fallback = match &f.default {
+            Some(expr) => quote! {
+                values.insert(#column.to_string(), #private::serde_json::to_value::<#ty>(#expr)?);
+            },
+            None => quote! {
+                defaults.insert(
+                    #column.to_string(),
+                    #private::serde_json::to_value(
+                        <#ty as ::sqlx_db_tester::Fake>::fake(#column, n),
+                    )?,
+                );
+            },
+        };
+        quote! {
+            match self.#ident {
+                ::core::option::Option::Some(value) => {
+                    values.insert(#column.to_string(), #private::serde_json::to_value(value)?);
+                }
+                ::core::option::Option::None => { #fallback }
+            }
+        }
+    });
+    let doc = format!(
+        "Builds `{table}` rows for [`{name}`].",
+        table = table.value()
+    );
+
+    Ok(quote! {
+        #[doc = #doc]
+        #vis struct #factory {
+            #( #idents: ::core::option::Option<#types>, )*
+        }
+
+        impl #name {
+            /// Start building a row with generated defaults for every field that isn't set.
+            #vis fn factory() -> #factory {
+                #factory {
+                    #( #idents: ::core::option::Option::None, )*
+                }
+            }
+        }
+
+        impl #factory {
+            #(
+                #vis fn #idents(mut self, value: impl ::core::convert::Into<#types>) -> Self {
+                    self.#idents = ::core::option::Option::Some(value.into());
+                    self
+                }
+            )*
+
+            /// Insert the row, creating parent rows for required foreign keys that
+            /// weren't set, and read it back.
+            #vis async fn create<D>(self, db: &D) -> #private::anyhow::Result<#name>
+            where
+                D: ::sqlx_db_tester::FactoryDb,
+                #name: for<'r> #private::sqlx::FromRow<'r, D::Row>,
+            {
+                #[allow(unused_variables)]
+                let n = #private::next_sequence();
+                let mut values = #private::serde_json::Map::new();
+                let mut defaults = #private::serde_json::Map::new();
+                #( #collect )*
+                let row = db.insert_factory_row(#table, values, defaults).await?;
+                ::core::result::Result::Ok(
+                    <#name as #private::sqlx::FromRow<'_, D::Row>>::from_row(&row)?,
+                )
+            }
+        }
+    })
+}
+
+/// Applies a `#[sqlx(rename_all = "...")]` case to a field name, as `sqlx::FromRow`
+/// does.
+fn rename(name: &str, case: &LitStr) -> syn::Result<String> {
+    Ok(match case.value().as_str() {
+        "lowercase" => name.to_lowercase(),
+        "snake_case" => name.to_snake_case(),
+        "UPPERCASE" => name.to_uppercase(),
+        "SCREAMING_SNAKE_CASE" => name.to_shouty_snake_case(),
+        "kebab-case" => name.to_kebab_case(),
+        "camelCase" => name.to_lower_camel_case(),
+        "PascalCase" => name.to_upper_camel_case(),
+        _ => return Err(syn::Error::new_spanned(case, "unknown rename_all case")),
+    })
+}
+
+/// Skips an argument of a `#[sqlx(...)]` attribute that doesn't affect column names,
+/// along with its value.
+fn skip_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(token::Paren) {
+        meta.parse_nested_meta(|nested| skip_value(&nested))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use anyhow::{Result, bail};
+use serde_json::Value;
+use std::{collections::BTreeMap, str::FromStr, thread};
+
+/// Rows inserted, updated or deleted while running code under
+/// [`capture_changes`](crate::TestPg::capture_changes), grouped by table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Changes {
+    tables: BTreeMap<String, Vec<Change>>,
+}
+
+/// A single row-level change, in the order it was committed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub op: ChangeOp,
+    /// The row before the change, `None` for inserts.
+    pub before: Option<Value>,
+    /// The row after the change, `None` for deletes.
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Changes {
+    /// Changes made to a table, in the order they happened. Empty if it wasn't touched.
+    pub fn table(&self, table: &str) -> &[Change] {
+        self.tables
+            .get(table)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Names of the tables that were touched, in alphabetical order.
+    pub fn tables(&self) -> impl Iterator<Item = &str> {
+        self.tables.keys().map(String::as_str)
+    }
+
+    /// Iterate over every change as `(table, change)`, grouped by table.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Change)> {
+        self.tables
+            .iter()
+            .flat_map(|(table, changes)| changes.iter().map(move |c| (table.as_str(), c)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, table: String, change: Change) {
+        self.tables.entry(table).or_default().push(change);
+    }
+}
+
+impl FromStr for ChangeOp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "INSERT" => Ok(Self::Insert),
+            "UPDATE" => Ok(Self::Update),
+            "DELETE" => Ok(Self::Delete),
+            _ => bail!("unknown change operation {s}"),
+        }
+    }
+}
+
+/// Removes the triggers of a capture when `capture_changes` is left early, by an
+/// error while installing them or a panic in the code under test. The teardown runs
+/// on a thread of its own, since it can't be awaited from `Drop`.
+pub(crate) struct CaptureGuard<F: FnOnce() + Send + 'static>(Option<F>);
+
+impl<F: FnOnce() + Send + 'static> CaptureGuard<F> {
+    pub fn new(teardown: F) -> Self {
+        Self(Some(teardown))
+    }
+
+    /// Called once the capture was removed normally.
+    pub fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl<F: FnOnce() + Send + 'static> Drop for CaptureGuard<F> {
+    fn drop(&mut self) {
+        if let Some(teardown) = self.0.take() {
+            let _ = thread::spawn(teardown).join();
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::generate::{self, RowStore};
+use anyhow::{Result, bail};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashSet,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use uuid::Uuid;
+
+/// How many levels of required foreign keys are followed when creating parent rows.
+const MAX_PARENT_DEPTH: usize = 8;
+
+/// A test database that rows built by a [`Factory`](derive@crate::Factory) can be
+/// inserted into. Implemented by [`TestPg`](crate::TestPg) and
+/// [`TestMySql`](crate::TestMySql).
+#[allow(async_fn_in_trait)]
+pub trait FactoryDb {
+    type Row: sqlx::Row;
+
+    /// Insert a row into `table` and read it back.
+    ///
+    /// `values` were set explicitly and are always inserted. `defaults` were generated
+    /// and only fill columns the database has no default for; required foreign keys
+    /// missing from `values` are pointed at newly created parent rows instead.
+    async fn insert_factory_row(
+        &self,
+        table: &str,
+        values: Map<String, Value>,
+        defaults: Map<String, Value>,
+    ) -> Result<Self::Row>;
+}
+
+/// Generates the default value of a factory field from the column name and the
+/// row's sequence number.
+///
+/// Strings get fake names or email addresses when the column name suggests one, and
+/// the column name followed by the sequence number otherwise. Numbers get the
+/// sequence number, wrapped around for types too small to hold it, timestamps and
+/// UUIDs are derived from it, and options are `None`, so rows are the same on every
+/// run.
+pub trait Fake {
+    fn fake(column: &str, n: u64) -> Self;
+}
+
+impl Fake for String {
+    fn fake(column: &str, n: u64) -> Self {
+        generate::fake_string(column, n)
+    }
+}
+
+macro_rules! fake_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl Fake for $ty {
+                fn fake(_column: &str, n: u64) -> Self {
+                    <$ty>::try_from(n).unwrap_or_else(|_| (n % <$ty>::MAX as u64) as $ty)
+                }
+            }
+        )*
+    };
+}
+
+fake_integer!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl Fake for f32 {
+    fn fake(_column: &str, n: u64) -> Self {
+        n as f32
+    }
+}
+
+impl Fake for f64 {
+    fn fake(_column: &str, n: u64) -> Self {
+        n as f64
+    }
+}
+
+impl Fake for bool {
+    fn fake(_column: &str, _n: u64) -> Self {
+        false
+    }
+}
+
+impl<T> Fake for Option<T> {
+    fn fake(_column: &str, _n: u64) -> Self {
+        None
+    }
+}
+
+impl Fake for DateTime<Utc> {
+    fn fake(_column: &str, n: u64) -> Self {
+        generate::timestamp(n).and_utc()
+    }
+}
+
+impl Fake for NaiveDateTime {
+    fn fake(_column: &str, n: u64) -> Self {
+        generate::timestamp(n)
+    }
+}
+
+impl Fake for NaiveDate {
+    fn fake(_column: &str, n: u64) -> Self {
+        generate::timestamp(n).date()
+    }
+}
+
+impl Fake for Uuid {
+    fn fake(_column: &str, n: u64) -> Self {
+        generate::uuid(n)
+    }
+}
+
+impl Fake for Value {
+    fn fake(_column: &str, _n: u64) -> Self {
+        Value::Object(Map::new())
+    }
+}
+
+/// Returns a process-wide sequence number, unique for every generated row.
+pub fn next_sequence() -> u64 {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(1);
+    SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Serializes typed rows into maps from column names to values.
+pub(crate) fn json_rows<T: Serialize>(table: &str, rows: &[T]) -> Result<Vec<Map<String, Value>>> {
+    rows.iter()
+        .map(|row| match serde_json::to_value(row)? {
+            Value::Object(row) => Ok(row),
+            _ => bail!("rows inserted into {table} must serialize to maps"),
+        })
+        .collect()
+}
+
+/// Inserts a row made of `values`, the `defaults` for the columns the database can't
+/// fill, and generated values for the remaining required columns. Parent rows are
+/// created for required foreign keys that aren't set, recursively.
+pub(crate) fn insert_with_parents<'a, C: RowStore>(
+    conn: &'a mut C,
+    table: &'a str,
+    values: Map<String, Value>,
+    defaults: Map<String, Value>,
+    depth: usize,
+) -> Pin<Box<dyn Future<Output = Result<Value>> + 'a>> {
+    Box::pin(async move {
+        if depth > MAX_PARENT_DEPTH {
+            bail!("required foreign keys of {table} are nested too deeply to create parent rows");
+        }
+        let columns = conn.columns(table).await?;
+        let foreign_keys = conn.foreign_keys(table).await?;
+        let required = |name: &str| {
+            columns
+                .iter()
+                .any(|c| c.name == name && !c.nullable && !c.has_default)
+        };
+
+        let mut row = values;
+        for fk in &foreign_keys {
+            if fk.columns.iter().any(|c| row.contains_key(c))
+                || !fk.columns.iter().any(|c| required(c))
+            {
+                continue;
+            }
+            let parent =
+                insert_with_parents(conn, &fk.parent, Map::new(), Map::new(), depth + 1).await?;
+            for (column, parent_column) in fk.columns.iter().zip(&fk.parent_columns) {
+                let value = parent.get(parent_column).cloned().unwrap_or(Value::Null);
+                row.insert(column.clone(), value);
+            }
+        }
+
+        let fk_columns: HashSet<&str> = foreign_keys
+            .iter()
+            .flat_map(|fk| fk.columns.iter().map(String::as_str))
+            .collect();
+        let n = next_sequence();
+        for column in &columns {
+            if row.contains_key(&column.name)
+                || column.has_default
+                || fk_columns.contains(column.name.as_str())
+            {
+                continue;
+            }
+            match defaults.get(&column.name) {
+                Some(value) if !value.is_null() => {
+                    row.insert(column.name.clone(), value.clone());
+                }
+                _ if !column.nullable => {
+                    row.insert(column.name.clone(), generate::value(column, n)?);
+                }
+                _ => {}
+            }
+        }
+
+        conn.insert_json(table, &row).await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_should_be_deterministic_and_in_range() {
+        assert_eq!(i32::fake("id", 7), 7);
+        assert_eq!(i8::fake("rank", 300), (300 % 127) as i8);
+        assert_eq!(u8::fake("rank", 255), 255);
+        assert_eq!(
+            NaiveDateTime::fake("created_at", 61),
+            NaiveDateTime::fake("created_at", 61)
+        );
+        assert_eq!(
+            DateTime::<Utc>::fake("created_at", 61).to_rfc3339(),
+            "2024-01-01T01:01:00+00:00"
+        );
+        assert_eq!(Uuid::fake("id", 1), Uuid::fake("id", 1));
+        assert_ne!(Uuid::fake("id", 1), Uuid::fake("id", 2));
+    }
+}
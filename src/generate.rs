@@ -0,0 +1,465 @@
+use anyhow::{Result, bail};
+use chrono::{NaiveDate, TimeDelta};
+use serde_json::{Map, Value, json};
+use std::{collections::HashSet, future::Future, pin::Pin};
+use uuid::Uuid;
+
+/// Parent tables of populated tables that are still empty get one row per this many
+/// child rows.
+const CHILDREN_PER_PARENT: usize = 10;
+
+/// How many existing parent keys are sampled for the foreign keys of populated rows,
+/// picked at random from all the parents.
+const PARENT_SAMPLE: usize = 1000;
+
+const FIRST_NAMES: &[&str] = &[
+    "Alice", "Bob", "Carol", "Dave", "Erin", "Frank", "Grace", "Heidi", "Ivan", "Judy",
+];
+const LAST_NAMES: &[&str] = &[
+    "Smith", "Johnson", "Lee", "Brown", "Garcia", "Miller", "Davis", "Wilson", "Moore", "Clark",
+];
+
+/// A column as seen by the row generator.
+#[derive(Debug, Clone)]
+pub(crate) struct ColumnInfo {
+    pub name: String,
+    /// Type as reported by the database, e.g. `character varying(255)` or `varchar(255)`.
+    pub data_type: String,
+    pub nullable: bool,
+    /// Whether the database fills the column when it's left out (a default, a
+    /// sequence, an identity or a generated column).
+    pub has_default: bool,
+    /// Whether a unique index or the primary key covers just this column.
+    pub unique: bool,
+    /// Allowed values of an enum column or of a column restricted by a `CHECK (... IN
+    /// (...))` constraint, empty for other columns.
+    pub labels: Vec<String>,
+}
+
+/// A foreign key of a table, with its columns in the order they're declared.
+#[derive(Debug, Clone)]
+pub(crate) struct ForeignKey {
+    pub columns: Vec<String>,
+    pub parent: String,
+    pub parent_columns: Vec<String>,
+}
+
+/// A connection that rows can be generated for and inserted through, with rows
+/// passed around as JSON objects.
+pub(crate) trait RowStore {
+    async fn columns(&mut self, table: &str) -> Result<Vec<ColumnInfo>>;
+    async fn foreign_keys(&mut self, table: &str) -> Result<Vec<ForeignKey>>;
+    /// Inserts a single row and returns it as stored, with generated columns filled.
+    async fn insert_json(&mut self, table: &str, row: &Map<String, Value>) -> Result<Value>;
+    /// Inserts rows that all set the same columns.
+    async fn insert_batch(&mut self, table: &str, rows: &[Map<String, Value>]) -> Result<()>;
+    /// Returns `limit` rows of a table, each as an array of the given columns, in an
+    /// order shuffled by `seed` so the same seed picks the same rows.
+    async fn sample(
+        &mut self,
+        table: &str,
+        columns: &[String],
+        limit: usize,
+        seed: u64,
+    ) -> Result<Vec<Value>>;
+    async fn count(&mut self, table: &str) -> Result<u64>;
+}
+
+/// SplitMix64, which is plenty for test data and reproducible across platforms.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Inserts `n` generated rows into `table`.
+///
+/// Unique columns get sequential values following the rows already in the table,
+/// other columns values drawn from `rng`. Foreign keys point to random existing
+/// parent rows; empty parent tables are populated first when the key is required.
+/// Columns the database fills itself are left out.
+pub(crate) fn populate<'a, C: RowStore>(
+    conn: &'a mut C,
+    table: &'a str,
+    n: usize,
+    rng: &'a mut Rng,
+    visiting: &'a mut Vec<String>,
+) -> Pin<Box<dyn Future<Output = Result<()>> + 'a>> {
+    Box::pin(async move {
+        if visiting.iter().any(|t| t == table) {
+            bail!(
+                "can't populate {table}: required foreign keys form a cycle ({} -> {table})",
+                visiting.join(" -> ")
+            );
+        }
+        let columns = conn.columns(table).await?;
+        let foreign_keys = conn.foreign_keys(table).await?;
+        let required = |name: &str| {
+            columns
+                .iter()
+                .any(|c| c.name == name && !c.nullable && !c.has_default)
+        };
+        let unique = |name: &str| columns.iter().any(|c| c.name == name && c.unique);
+
+        let mut parents = vec![];
+        for fk in &foreign_keys {
+            let seed = rng.next_u64();
+            let mut keys = conn
+                .sample(&fk.parent, &fk.parent_columns, PARENT_SAMPLE, seed)
+                .await?;
+            if keys.is_empty() && fk.columns.iter().any(|c| required(c)) {
+                visiting.push(table.to_string());
+                let parent_rows = n.div_ceil(CHILDREN_PER_PARENT).max(1);
+                populate(conn, &fk.parent, parent_rows, rng, visiting).await?;
+                visiting.pop();
+                keys = conn
+                    .sample(&fk.parent, &fk.parent_columns, PARENT_SAMPLE, seed)
+                    .await?;
+            }
+            parents.push((fk, keys, fk.columns.iter().any(|c| unique(c))));
+        }
+
+        let fk_columns: HashSet<&str> = foreign_keys
+            .iter()
+            .flat_map(|fk| fk.columns.iter().map(String::as_str))
+            .collect();
+        let offset = conn.count(table).await?;
+        let mut rows = Vec::with_capacity(n);
+        for i in 0..n {
+            let mut row = Map::new();
+            for (fk, keys, unique) in &parents {
+                let key = match (keys.len(), unique) {
+                    (0, _) => None,
+                    (len, true) => keys.get(i % len),
+                    (len, false) => keys.get(rng.below(len)),
+                };
+                for (j, column) in fk.columns.iter().enumerate() {
+                    let value = key.and_then(|k| k.get(j)).cloned().unwrap_or(Value::Null);
+                    row.insert(column.clone(), value);
+                }
+            }
+            for column in &columns {
+                if column.has_default || fk_columns.contains(column.name.as_str()) {
+                    continue;
+                }
+                let k = if column.unique {
+                    offset + i as u64 + 1
+                } else {
+                    rng.next_u64() % 1_000_000
+                };
+                row.insert(column.name.clone(), value(column, k)?);
+            }
+            rows.push(row);
+        }
+
+        if rows.first().is_some_and(Map::is_empty) {
+            for row in &rows {
+                conn.insert_json(table, row).await?;
+            }
+        } else if !rows.is_empty() {
+            conn.insert_batch(table, &rows).await?;
+        }
+        Ok(())
+    })
+}
+
+/// Generates a value for a column from a number: the row's sequence number for
+/// factories, a random one when populating tables.
+///
+/// Numbers of other columns are kept small, but unique columns get `n` itself, which
+/// fails when the column's type can't hold it.
+pub(crate) fn value(column: &ColumnInfo, n: u64) -> Result<Value> {
+    if !column.labels.is_empty() {
+        return Ok(json!(column.labels[n as usize % column.labels.len()]));
+    }
+    let t = column.data_type.to_lowercase();
+    let unsigned = t.contains("unsigned");
+    // `m` bounds the values of other columns, `max` those of unique ones
+    let integer = |m: u64, max: u64| {
+        if !column.unique {
+            Ok(n % m)
+        } else if n <= max {
+            Ok(n)
+        } else {
+            bail!(
+                "can't generate unique value {n} for column {} of type {}: out of range",
+                column.name,
+                column.data_type
+            )
+        }
+    };
+    Ok(if t.ends_with("[]") {
+        json!([])
+    } else if t.starts_with("bool") || t == "tinyint(1)" {
+        json!(n.is_multiple_of(2))
+    } else if t.starts_with("tinyint") {
+        json!(integer(100, if unsigned { 255 } else { 127 })?)
+    } else if t.starts_with("smallint") {
+        json!(integer(30000, if unsigned { 65535 } else { 32767 })?)
+    } else if t.starts_with("mediumint") {
+        json!(integer(
+            1_000_000,
+            if unsigned { 16_777_215 } else { 8_388_607 }
+        )?)
+    } else if t.starts_with("int") {
+        json!(integer(
+            1_000_000_000,
+            if unsigned {
+                u32::MAX as u64
+            } else {
+                i32::MAX as u64
+            }
+        )?)
+    } else if t.starts_with("bigint") {
+        json!(integer(
+            1_000_000_000,
+            if unsigned { u64::MAX } else { i64::MAX as u64 }
+        )?)
+    } else if t.starts_with("numeric") || t.starts_with("decimal") {
+        // the digits before the point are limited to `precision - scale`
+        let (digits, scale) = match numeric_precision(&t) {
+            Some((precision, scale)) => (precision.saturating_sub(scale), scale),
+            None => (6, 1),
+        };
+        let max = 10u64.checked_pow(digits).unwrap_or(u64::MAX);
+        let k = integer(max.min(1_000_000), max - 1)?;
+        if scale > 0 {
+            json!(k as f64 + 0.5)
+        } else {
+            json!(k)
+        }
+    } else if ["real", "double", "float"].iter().any(|p| t.starts_with(p)) {
+        json!(integer(1_000_000, u64::MAX)? as f64 + 0.5)
+    } else if t.starts_with("timestamp") || t.starts_with("datetime") {
+        json!(timestamp(n).format("%Y-%m-%dT%H:%M:%S").to_string())
+    } else if t == "date" {
+        json!(timestamp(n).format("%Y-%m-%d").to_string())
+    } else if t.starts_with("time") {
+        json!(timestamp(n).format("%H:%M:%S").to_string())
+    } else if t == "uuid" {
+        json!(uuid(n).to_string())
+    } else if t.starts_with("json") {
+        json!({})
+    } else {
+        let mut s = fake_string(&column.name, n);
+        if column.unique && !s.contains(&n.to_string()) {
+            s = format!("{s} {n}");
+        }
+        // keep the distinguishing number when truncating a unique value
+        match max_length(&t) {
+            Some(max) if s.chars().count() > max && column.unique => {
+                let suffix = n.to_string();
+                let keep = max.saturating_sub(suffix.len());
+                json!(format!(
+                    "{}{suffix}",
+                    s.chars().take(keep).collect::<String>()
+                ))
+            }
+            Some(max) => json!(s.chars().take(max).collect::<String>()),
+            None => json!(s),
+        }
+    })
+}
+
+/// Returns the allowed values of a single-column `CHECK` constraint written as
+/// `col IN ('a', 'b')` or `col = ANY (ARRAY['a', 'b'])`, empty for other constraints.
+pub(crate) fn check_labels(clause: &str) -> Vec<String> {
+    let lower = clause.to_lowercase();
+    if !(lower.contains(" in ") || lower.contains(" in(") || lower.contains("any ("))
+        || lower.contains(" and ")
+        || lower.contains(" or ")
+    {
+        return vec![];
+    }
+    let mut labels = vec![];
+    let mut chars = clause.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\'' {
+            continue;
+        }
+        let mut label = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' if chars.peek() == Some(&'\'') => {
+                    label.push('\'');
+                    chars.next();
+                }
+                '\'' => break,
+                c => label.push(c),
+            }
+        }
+        labels.push(label);
+    }
+    labels
+}
+
+/// A string that looks like what the column holds, judging by its name: fake names
+/// and email addresses, or the column name followed by `n`.
+pub(crate) fn fake_string(column: &str, n: u64) -> String {
+    let first = FIRST_NAMES[n as usize % FIRST_NAMES.len()];
+    let last = LAST_NAMES[(n / FIRST_NAMES.len() as u64) as usize % LAST_NAMES.len()];
+    let column = column.to_lowercase();
+    if column.contains("email") {
+        format!("{}.{}{n}@example.com", first, last).to_lowercase()
+    } else if column == "first_name" || column == "given_name" {
+        first.to_string()
+    } else if column == "last_name" || column == "family_name" || column == "surname" {
+        last.to_string()
+    } else if column == "username" || column == "login" {
+        format!("{}{n}", first.to_lowercase())
+    } else if column == "name" || column.ends_with("_name") {
+        format!("{first} {last}")
+    } else {
+        format!("{column} {n}")
+    }
+}
+
+/// A timestamp `n` minutes after 2024-01-01 00:00:00, so generated rows are stable
+/// across runs.
+pub(crate) fn timestamp(n: u64) -> chrono::NaiveDateTime {
+    NaiveDate::from_ymd_opt(2024, 1, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .expect("valid date")
+        + TimeDelta::minutes((n % 1_000_000) as i64)
+}
+
+/// A UUID made from `n`, so generated rows are stable across runs.
+pub(crate) fn uuid(n: u64) -> Uuid {
+    Uuid::from_u128(n as u128 + 1)
+}
+
+/// Precision and scale of a `numeric(p, s)` / `decimal(p, s)` type.
+fn numeric_precision(data_type: &str) -> Option<(u32, u32)> {
+    let start = data_type.find('(')?;
+    let end = data_type[start..].find(')')? + start;
+    let mut parts = data_type[start + 1..end].split(',').map(str::trim);
+    let precision = parts.next()?.parse().ok()?;
+    let scale = parts.next().map_or(Ok(0), str::parse).ok()?;
+    Some((precision, scale))
+}
+
+/// Length limit of a `varchar(n)` / `character varying(n)` / `char(n)` type.
+fn max_length(data_type: &str) -> Option<usize> {
+    if !data_type.contains("char") {
+        return None;
+    }
+    let start = data_type.find('(')?;
+    let end = data_type[start..].find(')')? + start;
+    data_type[start + 1..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: &str) -> ColumnInfo {
+        ColumnInfo {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            nullable: false,
+            has_default: false,
+            unique: false,
+            labels: vec![],
+        }
+    }
+
+    #[test]
+    fn value_should_follow_column_type() {
+        assert_eq!(value(&column("id", "integer"), 7).unwrap(), json!(7));
+        assert_eq!(
+            value(&column("done", "tinyint(1)"), 1).unwrap(),
+            json!(false)
+        );
+        assert_eq!(
+            value(&column("created_at", "timestamp without time zone"), 61).unwrap(),
+            json!("2024-01-01T01:01:00")
+        );
+        assert_eq!(
+            value(&column("title", "character varying(8)"), 123).unwrap(),
+            json!("title 12")
+        );
+        let mut status = column("status", "todo_status");
+        status.labels = vec!["open".into(), "done".into()];
+        assert_eq!(value(&status, 3).unwrap(), json!("done"));
+    }
+
+    #[test]
+    fn fake_string_should_look_like_names() {
+        assert_eq!(fake_string("name", 11), "Bob Johnson");
+        assert_eq!(fake_string("email", 0), "alice.smith0@example.com");
+        assert_eq!(fake_string("title", 3), "title 3");
+    }
+
+    #[test]
+    fn unique_values_should_stay_distinct() {
+        let mut name = column("name", "varchar(8)");
+        name.unique = true;
+        assert_eq!(value(&name, 11).unwrap(), json!("Bob Jo11"));
+        assert_eq!(value(&name, 12345).unwrap(), json!("Fra12345"));
+        let mut id = column("code", "smallint");
+        id.unique = true;
+        assert_eq!(value(&id, 30000).unwrap(), json!(30000));
+        let err = value(&id, 40000).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "can't generate unique value 40000 for column code of type smallint: out of range"
+        );
+        id.data_type = "tinyint".to_string();
+        assert!(value(&id, 128).is_err());
+        id.data_type = "tinyint unsigned".to_string();
+        assert_eq!(value(&id, 255).unwrap(), json!(255));
+    }
+
+    #[test]
+    fn numeric_values_should_fit_precision_and_scale() {
+        assert_eq!(
+            value(&column("price", "numeric(5,2)"), 1234).unwrap(),
+            json!(234.5)
+        );
+        assert_eq!(
+            value(&column("price", "decimal(4,0)"), 12345).unwrap(),
+            json!(2345)
+        );
+        assert_eq!(value(&column("price", "numeric"), 7).unwrap(), json!(7.5));
+        let mut price = column("price", "numeric(5,2)");
+        price.unique = true;
+        assert_eq!(value(&price, 999).unwrap(), json!(999.5));
+        assert!(value(&price, 1000).is_err());
+    }
+
+    #[test]
+    fn check_labels_should_parse_in_lists() {
+        assert_eq!(
+            check_labels("CHECK ((status = ANY (ARRAY['open'::text, 'it''s done'::text])))"),
+            vec!["open", "it's done"]
+        );
+        assert_eq!(
+            check_labels("(`status` in (_utf8mb4'open',_utf8mb4'done'))"),
+            vec!["open", "done"]
+        );
+        assert!(check_labels("CHECK ((priority > 0))").is_empty());
+        assert!(check_labels("CHECK ((a IN ('x')) OR (b = 'y'))").is_empty());
+    }
+
+    #[test]
+    fn rng_should_be_reproducible() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        let xs: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        assert_eq!(xs, (0..4).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(xs[0], Rng::new(8).next_u64());
+    }
+}
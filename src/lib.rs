@@ -1,9 +1,91 @@
+// lets the code generated by the derive macros refer to this crate by name in its own tests
+extern crate self as sqlx_db_tester;
+
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod changes;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod factory;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod generate;
 #[cfg(feature = "mysql")]
 mod mysql;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod naming;
+#[cfg(feature = "postgres")]
+mod plan;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod pool;
 #[cfg(feature = "postgres")]
 mod postgres;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod schema;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod snapshot;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod statements;
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+mod tag;
 
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use changes::{Change, ChangeOp, Changes};
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use factory::{FactoryDb, Fake};
 #[cfg(feature = "mysql")]
-pub use mysql::TestMySql;
+pub use mysql::{IsolationLevel, TestMySql, TestMySqlBuilder};
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use naming::DbNaming;
+#[cfg(feature = "postgres")]
+pub use plan::{PlanNode, QueryPlan};
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use pool::LeakCheck;
 #[cfg(feature = "postgres")]
 pub use postgres::{TestPg, TestPgBuilder};
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use schema::{
+    ColumnSchema, Difference, SchemaDiff, SchemaSnapshot, SequenceSchema, TableSchema,
+};
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use snapshot::{SnapshotOptions, UPDATE_SNAPSHOTS_ENV};
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub use statements::{Statement, StatementLog};
+
+/// Derive a fixture builder for a row struct: `Todo::factory()` returns a
+/// `TodoFactory` whose `create(&tdb)` inserts the row into a [`TestPg`] or
+/// [`TestMySql`] and reads it back. Unset fields get a [`Fake`] value.
+///
+/// # Example
+/// ```no_run
+/// use sqlx_db_tester::{Factory, TestPg};
+///
+/// #[derive(sqlx::FromRow, Factory)]
+/// #[factory(table = "todos")]
+/// struct Todo {
+///     id: i32,
+///     user_id: i32,
+///     #[factory(default = format!("todo #{n}"))]
+///     title: String,
+/// }
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let tdb = TestPg::default();
+/// // creates the user the todo belongs to as well
+/// let todo = Todo::factory().create(&tdb).await?;
+/// let other = Todo::factory()
+///     .user_id(todo.user_id)
+///     .title("mine")
+///     .create(&tdb)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(all(feature = "derive", any(feature = "postgres", feature = "mysql")))]
+pub use sqlx_db_tester_derive::Factory;
+
+#[doc(hidden)]
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+pub mod __private {
+    pub use crate::factory::next_sequence;
+    pub use anyhow;
+    pub use serde_json;
+    pub use sqlx;
+}
@@ -0,0 +1,204 @@
+use std::thread;
+use uuid::Uuid;
+
+/// Room left after a test database's name for the databases derived from it, such
+/// as the scratch database of `check_reversibility` (`<name>_rev`).
+const DERIVED_SUFFIX_LEN: usize = 4;
+
+/// How a tester names the databases it creates.
+///
+/// Every strategy ends the name with a random id, and names are shortened to fit
+/// the server's identifier limit (63 bytes on PostgreSQL, 64 characters on MySQL).
+/// The descriptive part is cut, never the id, so truncated names stay unique. The
+/// prefix and the test name are sanitized to lowercase ASCII letters, digits and
+/// underscores.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DbNaming {
+    /// `<db>_test_<uuid>`, where `<db>` is the database of the URL, taken as is.
+    #[default]
+    Unique,
+    /// `<prefix>_<id>`, e.g. to tell apart the databases of several test suites
+    /// sharing a server.
+    Prefix(String),
+    /// `<db>_<test>_<id>`, where `<test>` is the name of the current thread. The test
+    /// harness runs each test on a thread named after its path, e.g.
+    /// `api::tests::creates_user`, so a leftover database can be traced back to the
+    /// test that created it. Falls back to `<db>_test_<id>` on the main thread or an
+    /// unnamed one.
+    TestName,
+}
+
+impl DbNaming {
+    /// A new database name no longer than `max_len` bytes. `base` is the database of
+    /// the URL, if any.
+    pub(crate) fn database_name(&self, base: Option<&str>, max_len: usize) -> String {
+        let (stem, id) = match self {
+            Self::Unique => {
+                let stem = match base {
+                    Some(base) => format!("{base}_test"),
+                    None => "test".to_string(),
+                };
+                (stem, Uuid::new_v4().simple().to_string())
+            }
+            Self::Prefix(prefix) => (sanitize(prefix), short_id()),
+            Self::TestName => {
+                let test = current_test()
+                    .map(|test| sanitize(&test))
+                    .unwrap_or_else(|| "test".to_string());
+                let stem = match base.map(sanitize) {
+                    Some(base) => format!("{base}_{test}"),
+                    None => test,
+                };
+                (stem, short_id())
+            }
+        };
+        with_suffix(&stem, &id, max_len - DERIVED_SUFFIX_LEN)
+    }
+}
+
+/// Whether `name` could have been created with [`DbNaming::Prefix`] and `prefix` by
+/// a tester whose names are limited to `max_len` bytes.
+pub(crate) fn has_prefix(name: &str, prefix: &str, max_len: usize) -> bool {
+    let stem = sanitize(prefix);
+    let stem = truncate(&stem, max_len - DERIVED_SUFFIX_LEN - SHORT_ID_LEN - 1);
+    name.strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix('_'))
+        .is_some_and(|id| id.len() == SHORT_ID_LEN)
+}
+
+/// The path of the test running on the current thread, as named by the test
+/// harness.
+pub(crate) fn current_test() -> Option<String> {
+    thread::current()
+        .name()
+        .filter(|name| *name != "main")
+        .map(str::to_string)
+}
+
+/// Turns `name` into a stem that never needs quoting: lowercase ASCII letters,
+/// digits and single underscores.
+pub(crate) fn sanitize(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    for c in name.chars() {
+        let c = c.to_ascii_lowercase();
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            sanitized.push(c);
+        } else if !sanitized.is_empty() && !sanitized.ends_with('_') {
+            sanitized.push('_');
+        }
+    }
+    let sanitized = sanitized.trim_end_matches('_');
+    if sanitized.is_empty() {
+        "test".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+/// `<stem>_<suffix>`, cutting the end of the stem so the whole fits in `max_len`
+/// bytes.
+pub(crate) fn with_suffix(stem: &str, suffix: &str, max_len: usize) -> String {
+    let stem = truncate(stem, max_len.saturating_sub(suffix.len() + 1));
+    format!("{stem}_{suffix}")
+}
+
+/// `name`, or when it's longer than `max_len` bytes its longest prefix that fits,
+/// without a trailing underscore.
+pub(crate) fn truncate(name: &str, max_len: usize) -> &str {
+    if name.len() <= max_len {
+        return name;
+    }
+    let mut end = max_len;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name[..end].trim_end_matches('_')
+}
+
+/// Length of [`short_id`], 48 random bits.
+const SHORT_ID_LEN: usize = 12;
+
+/// 48 random bits, plenty to tell apart the databases that exist at the same time.
+fn short_id() -> String {
+    Uuid::new_v4().simple().to_string()[..SHORT_ID_LEN].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_should_keep_safe_characters_only() {
+        assert_eq!(
+            sanitize("api::tests::Creates-User"),
+            "api_tests_creates_user"
+        );
+        assert_eq!(sanitize("__héllo wörld__"), "h_llo_w_rld");
+        assert_eq!(sanitize("\"; DROP DATABASE x; --"), "drop_database_x");
+        assert_eq!(sanitize("::"), "test");
+    }
+
+    #[test]
+    fn with_suffix_should_keep_the_suffix_when_truncating() {
+        assert_eq!(with_suffix("short", "abc", 63), "short_abc");
+        let name = with_suffix(&"a".repeat(100), "0123456789ab", 63);
+        assert_eq!(name.len(), 63);
+        assert!(name.ends_with("a_0123456789ab"));
+        // never leaves a doubled underscore behind
+        assert_eq!(with_suffix("ab_cd", "x", 5), "ab_x");
+        // respects multi-byte characters in names that weren't sanitized
+        assert_eq!(with_suffix("ééé", "x", 5), "é_x");
+    }
+
+    #[test]
+    fn database_name_should_fit_the_limit_and_stay_unique() {
+        let naming = DbNaming::Prefix("a".repeat(80));
+        let first = naming.database_name(None, 63);
+        let second = naming.database_name(None, 63);
+        assert_eq!(first.len(), 63 - DERIVED_SUFFIX_LEN);
+        assert_ne!(first, second);
+
+        let name = DbNaming::Unique.database_name(Some(&"b".repeat(80)), 64);
+        assert_eq!(name.len(), 64 - DERIVED_SUFFIX_LEN);
+        assert_eq!(DbNaming::Unique.database_name(None, 64).len(), 37);
+    }
+
+    #[test]
+    fn unique_name_should_keep_the_database_name() {
+        let name = DbNaming::Unique.database_name(Some("My-App"), 63);
+        assert!(name.starts_with("My-App_test_"), "{name}");
+        assert_eq!(name.len(), "My-App_test_".len() + 32);
+    }
+
+    #[test]
+    fn has_prefix_should_match_the_names_of_a_prefix() {
+        let name = DbNaming::Prefix("Billing Suite".to_string()).database_name(None, 63);
+        assert!(has_prefix(&name, "Billing Suite", 63));
+        assert!(has_prefix(&name, "billing_suite", 63));
+        assert!(!has_prefix(&name, "billing", 63));
+        assert!(!has_prefix("billing_suite_test", "billing_suite", 63));
+
+        let long = "a".repeat(80);
+        let name = DbNaming::Prefix(long.clone()).database_name(None, 63);
+        assert!(has_prefix(&name, &long, 63));
+    }
+
+    #[test]
+    fn database_name_should_include_the_test_name() {
+        let name = thread::Builder::new()
+            .name("api::tests::creates_user".to_string())
+            .spawn(|| DbNaming::TestName.database_name(Some("App"), 63))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(name.starts_with("app_api_tests_creates_user_"), "{name}");
+
+        let name = thread::Builder::new()
+            .name("main".to_string())
+            .spawn(|| DbNaming::TestName.database_name(Some("app"), 63))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(name.starts_with("app_test_"), "{name}");
+    }
+}
@@ -0,0 +1,277 @@
+use crate::snapshot::{UPDATE_SNAPSHOTS_ENV, line_diff};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, env, fmt, fmt::Write, fs, path::Path};
+
+/// A query plan as reported by `EXPLAIN (FORMAT JSON)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    pub root: PlanNode,
+}
+
+/// A node of a [`QueryPlan`] tree.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PlanNode {
+    #[serde(rename = "Node Type")]
+    pub node_type: String,
+    #[serde(rename = "Relation Name")]
+    pub relation: Option<String>,
+    #[serde(rename = "Index Name")]
+    pub index: Option<String>,
+    #[serde(rename = "Plans", default)]
+    pub children: Vec<PlanNode>,
+    /// Every other property of the node (costs, filters, join type, ...).
+    #[serde(flatten)]
+    pub details: Map<String, Value>,
+}
+
+impl QueryPlan {
+    /// Parses the single row returned by `EXPLAIN (FORMAT JSON)`.
+    pub(crate) fn from_explain(explain: Value) -> Result<Self> {
+        let plan = explain
+            .get(0)
+            .and_then(|v| v.get("Plan"))
+            .context("EXPLAIN output has no plan")?;
+        Ok(Self {
+            root: PlanNode::deserialize(plan)?,
+        })
+    }
+
+    /// Every node of the plan, depth first.
+    pub fn nodes(&self) -> Vec<&PlanNode> {
+        let mut nodes = vec![];
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            stack.extend(node.children.iter().rev());
+        }
+        nodes
+    }
+
+    /// Whether any node scans the given index.
+    pub fn uses_index(&self, index: &str) -> bool {
+        self.nodes()
+            .iter()
+            .any(|n| n.index.as_deref() == Some(index))
+    }
+
+    /// The relations read with a sequential scan.
+    pub fn seq_scans(&self) -> Vec<&str> {
+        self.nodes()
+            .iter()
+            .filter(|n| n.node_type == "Seq Scan")
+            .filter_map(|n| n.relation.as_deref())
+            .collect()
+    }
+
+    /// The structure of the plan without costs, row estimates or conditions: one line
+    /// per node with its type, join type, index and relation.
+    ///
+    /// Two plans with the same shape access the data the same way, so shapes are
+    /// stable across runs and are what plan baselines record.
+    pub fn shape(&self) -> String {
+        let mut out = String::new();
+        self.root.fmt_shape(&mut out, 0);
+        out
+    }
+}
+
+impl PlanNode {
+    /// One-line description in the style of `EXPLAIN`'s text format.
+    pub fn label(&self) -> String {
+        let mut label = self.node_type.clone();
+        if let Some(index) = &self.index {
+            label.push_str(&format!(" using {index}"));
+        }
+        if let Some(relation) = &self.relation {
+            label.push_str(&format!(" on {relation}"));
+        }
+        label
+    }
+
+    fn fmt_shape(&self, out: &mut String, depth: usize) {
+        let mut label = self.node_type.clone();
+        if let Some(Value::String(join)) = self.details.get("Join Type") {
+            label.push_str(&format!(" ({join})"));
+        }
+        if let Some(index) = &self.index {
+            label.push_str(&format!(" using {index}"));
+        }
+        if let Some(relation) = &self.relation {
+            label.push_str(&format!(" on {relation}"));
+        }
+        let _ = writeln!(out, "{}-> {label}", "  ".repeat(depth));
+        for child in &self.children {
+            child.fmt_shape(out, depth + 1);
+        }
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        writeln!(f, "{indent}-> {}", self.label())?;
+        for key in ["Index Cond", "Filter", "Hash Cond", "Join Filter"] {
+            if let Some(Value::String(cond)) = self.details.get(key) {
+                writeln!(f, "{indent}     {key}: {cond}")?;
+            }
+        }
+        for child in &self.children {
+            child.fmt_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.fmt_tree(f, 0)
+    }
+}
+
+/// Compares the shapes of named query plans with the baseline file at `path`, or
+/// rewrites the file when [`UPDATE_SNAPSHOTS_ENV`] is set.
+///
+/// The baseline holds one section per query, introduced by a `# name` line and
+/// followed by the plan shape, in name order.
+pub(crate) fn assert_plan_baseline(plans: &BTreeMap<String, QueryPlan>, path: &Path) -> Result<()> {
+    let actual: String = plans
+        .iter()
+        .map(|(name, plan)| format!("# {name}\n{}", plan.shape()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if env::var_os(UPDATE_SNAPSHOTS_ENV).is_some() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, actual)?;
+        return Ok(());
+    }
+
+    let baseline = fs::read_to_string(path).with_context(|| {
+        format!(
+            "plan baseline {} is missing, run with {UPDATE_SNAPSHOTS_ENV}=1 to create it",
+            path.display()
+        )
+    })?;
+    let out = plan_baseline_diff(plans, &baseline);
+    if !out.is_empty() {
+        bail!(
+            "query plans do not match baseline {} (run with {UPDATE_SNAPSHOTS_ENV}=1 to update it):\n{}",
+            path.display(),
+            out.trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Describes how the shapes of `plans` differ from a baseline, or returns an empty
+/// string when they match.
+pub(crate) fn plan_baseline_diff(plans: &BTreeMap<String, QueryPlan>, baseline: &str) -> String {
+    let expected = parse_baseline(baseline);
+
+    let mut out = String::new();
+    for (name, plan) in plans {
+        let shape = plan.shape();
+        match expected.get(name.as_str()) {
+            Some(e) if *e == shape => {}
+            Some(e) => {
+                let _ = writeln!(out, "query {name}:\n{}", line_diff(e, &shape));
+                let _ = writeln!(out, "current plan:\n{plan}");
+            }
+            None => {
+                let _ = writeln!(out, "query {name} is not in the baseline\n");
+            }
+        }
+    }
+    for name in expected.keys().filter(|n| !plans.contains_key(**n)) {
+        let _ = writeln!(
+            out,
+            "query {name} is in the baseline but was not explained\n"
+        );
+    }
+    out
+}
+
+/// Splits a baseline file into plan shapes keyed by query name.
+fn parse_baseline(baseline: &str) -> BTreeMap<&str, String> {
+    let mut plans = BTreeMap::new();
+    let mut current: Option<(&str, String)> = None;
+    for line in baseline.lines() {
+        if let Some(name) = line.strip_prefix("# ") {
+            plans.extend(current.take());
+            current = Some((name.trim(), String::new()));
+        } else if let Some((_, shape)) = &mut current
+            && !line.trim().is_empty()
+        {
+            shape.push_str(line);
+            shape.push('\n');
+        }
+    }
+    plans.extend(current);
+    plans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn plan() -> QueryPlan {
+        QueryPlan::from_explain(json!([{
+            "Plan": {
+                "Node Type": "Nested Loop",
+                "Total Cost": 10.5,
+                "Plans": [
+                    {
+                        "Node Type": "Index Scan",
+                        "Index Name": "todos_pkey",
+                        "Relation Name": "todos",
+                        "Index Cond": "(id = 1)"
+                    },
+                    {
+                        "Node Type": "Seq Scan",
+                        "Relation Name": "tags",
+                        "Filter": "(name = 'a'::text)"
+                    }
+                ]
+            }
+        }]))
+        .unwrap()
+    }
+
+    #[test]
+    fn plan_should_find_indexes_and_seq_scans() {
+        let plan = plan();
+        assert_eq!(plan.nodes().len(), 3);
+        assert!(plan.uses_index("todos_pkey"));
+        assert!(!plan.uses_index("tags_pkey"));
+        assert_eq!(plan.seq_scans(), vec!["tags"]);
+    }
+
+    #[test]
+    fn plan_should_print_as_tree() {
+        assert_eq!(
+            plan().to_string(),
+            "-> Nested Loop\n  -> Index Scan using todos_pkey on todos\n       Index Cond: (id = 1)\n  \
+             -> Seq Scan on tags\n       Filter: (name = 'a'::text)\n"
+        );
+    }
+
+    #[test]
+    fn plan_shape_should_ignore_costs_and_conditions() {
+        assert_eq!(
+            plan().shape(),
+            "-> Nested Loop\n  -> Index Scan using todos_pkey on todos\n  -> Seq Scan on tags\n"
+        );
+    }
+
+    #[test]
+    fn baseline_should_parse_into_named_shapes() {
+        let plans =
+            parse_baseline("# by_id\n-> Index Scan on todos\n\n# all\n-> Seq Scan on todos\n");
+        assert_eq!(plans.len(), 2);
+        assert_eq!(plans["by_id"], "-> Index Scan on todos\n");
+        assert_eq!(plans["all"], "-> Seq Scan on todos\n");
+    }
+}
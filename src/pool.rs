@@ -0,0 +1,244 @@
+use crate::statements::unrecorded;
+use itertools::Itertools;
+use sqlx::{
+    Database, Pool,
+    pool::{PoolConnectionMetadata, PoolOptions},
+};
+use std::{
+    collections::HashMap, fmt, future::Future, hash::Hash, pin::Pin, sync::Arc, thread,
+    time::Duration,
+};
+use tokio::{
+    runtime::{self, RuntimeFlavor},
+    sync::Mutex,
+};
+
+/// How long teardown waits for connections checked out of a tester's pool to be
+/// returned before closing it anyway.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What a tester does about connections to its database that are still open when
+/// it's dropped, e.g. held by a leaked transaction or by a pool that was never
+/// closed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeakCheck {
+    /// Don't look for open connections.
+    Off,
+    /// Print the open connections and their last query to stderr.
+    #[default]
+    Warn,
+    /// Panic with the open connections and their last query, unless the thread is
+    /// already panicking.
+    Fail,
+}
+
+pub(crate) type BoxFuture<'c, T> = Pin<Box<dyn Future<Output = T> + Send + 'c>>;
+
+type AfterConnect<DB> = Arc<
+    dyn for<'c> Fn(&'c mut <DB as Database>::Connection) -> BoxFuture<'c, Result<(), sqlx::Error>>
+        + Send
+        + Sync,
+>;
+
+/// The pool options set on a tester's builder.
+pub(crate) struct PoolConfig<DB: Database> {
+    pub max_connections: Option<u32>,
+    pub min_connections: Option<u32>,
+    pub acquire_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    after_connect: Vec<AfterConnect<DB>>,
+}
+
+impl<DB: Database> PoolConfig<DB> {
+    /// Adds a hook run on every new connection, after the ones added before.
+    pub fn after_connect<F>(&mut self, hook: F)
+    where
+        F: for<'c> Fn(&'c mut DB::Connection) -> BoxFuture<'c, Result<(), sqlx::Error>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.after_connect.push(Arc::new(hook));
+    }
+
+    /// Adds a hook run on every new connection, before the ones added so far.
+    pub fn prepend_after_connect<F>(&mut self, hook: F)
+    where
+        F: for<'c> Fn(&'c mut DB::Connection) -> BoxFuture<'c, Result<(), sqlx::Error>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.after_connect.insert(0, Arc::new(hook));
+    }
+
+    pub fn options(&self) -> PoolOptions<DB> {
+        let mut options = PoolOptions::new();
+        if let Some(max) = self.max_connections {
+            options = options.max_connections(max);
+        }
+        if let Some(min) = self.min_connections {
+            options = options.min_connections(min);
+        }
+        if let Some(timeout) = self.acquire_timeout {
+            options = options.acquire_timeout(timeout);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            options = options.idle_timeout(timeout);
+        }
+        if !self.after_connect.is_empty() {
+            let hooks = self.after_connect.clone();
+            options = options.after_connect(
+                move |conn: &mut DB::Connection, _meta: PoolConnectionMetadata| {
+                    let hooks = hooks.clone();
+                    Box::pin(unrecorded(async move {
+                        for hook in &hooks {
+                            hook(&mut *conn).await?;
+                        }
+                        Ok(())
+                    }))
+                },
+            );
+        }
+        options
+    }
+}
+
+impl<DB: Database> Default for PoolConfig<DB> {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            min_connections: None,
+            acquire_timeout: None,
+            idle_timeout: None,
+            after_connect: vec![],
+        }
+    }
+}
+
+impl<DB: Database> Clone for PoolConfig<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            max_connections: self.max_connections,
+            min_connections: self.min_connections,
+            acquire_timeout: self.acquire_timeout,
+            idle_timeout: self.idle_timeout,
+            after_connect: self.after_connect.clone(),
+        }
+    }
+}
+
+impl<DB: Database> fmt::Debug for PoolConfig<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolConfig")
+            .field("max_connections", &self.max_connections)
+            .field("min_connections", &self.min_connections)
+            .field("acquire_timeout", &self.acquire_timeout)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("after_connect", &self.after_connect.len())
+            .finish()
+    }
+}
+
+/// Lazily created pools, one per key, shared by every caller on the same runtime.
+///
+/// Connections are tied to the runtime that opened them, so a tester used from
+/// several runtimes (e.g. a static shared by `#[tokio::test]`s) gets a new pool
+/// whenever the runtime changes.
+pub(crate) struct PoolCache<DB: Database, K = ()> {
+    pools: Mutex<HashMap<K, (runtime::Id, Pool<DB>)>>,
+}
+
+impl<DB: Database> PoolCache<DB> {
+    pub async fn get_or_connect<F>(&self, connect: F) -> Result<Pool<DB>, sqlx::Error>
+    where
+        F: Future<Output = Result<Pool<DB>, sqlx::Error>>,
+    {
+        self.get_or_connect_with((), connect).await
+    }
+
+    pub fn take(&mut self) -> Option<Pool<DB>> {
+        self.take_all().pop()
+    }
+}
+
+impl<DB: Database, K: Eq + Hash> PoolCache<DB, K> {
+    /// The pool cached for `key`, or the one `connect` opens.
+    pub async fn get_or_connect_with<F>(&self, key: K, connect: F) -> Result<Pool<DB>, sqlx::Error>
+    where
+        F: Future<Output = Result<Pool<DB>, sqlx::Error>>,
+    {
+        let id = runtime::Handle::current().id();
+        let mut cached = self.pools.lock().await;
+        if let Some((runtime, pool)) = cached.get(&key)
+            && *runtime == id
+            && !pool.is_closed()
+        {
+            return Ok(pool.clone());
+        }
+        let pool = connect.await?;
+        cached.insert(key, (id, pool.clone()));
+        Ok(pool)
+    }
+
+    pub fn take_all(&mut self) -> Vec<Pool<DB>> {
+        self.pools
+            .get_mut()
+            .drain()
+            .map(|(_, (_, pool))| pool)
+            .collect()
+    }
+}
+
+impl<DB: Database, K> Default for PoolCache<DB, K> {
+    fn default() -> Self {
+        Self {
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<DB: Database, K> fmt::Debug for PoolCache<DB, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolCache").finish_non_exhaustive()
+    }
+}
+
+/// Closes a pool from a tester's `Drop`, which has to run the returned future on
+/// another thread. It resolves to whether every connection was returned, as far as
+/// can be told.
+///
+/// Connections still checked out are given a moment to be returned, unless the
+/// caller is blocking a current-thread runtime: the tasks returning them would only
+/// run on that runtime, so connections dropped just before can't be told from ones
+/// that are still held.
+pub(crate) fn close<DB: Database>(pool: Pool<DB>) -> impl Future<Output = bool> + Send {
+    let blocking = matches!(
+        runtime::Handle::try_current(),
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::CurrentThread
+    );
+    let timeout = if blocking {
+        Duration::ZERO
+    } else {
+        CLOSE_TIMEOUT
+    };
+    async move { tokio::time::timeout(timeout, pool.close()).await.is_ok() || blocking }
+}
+
+/// Reports the connections a tester found open at teardown, as configured.
+pub(crate) fn report_leaks(check: LeakCheck, dbname: &str, leaks: &[String]) {
+    if leaks.is_empty() {
+        return;
+    }
+    let report = format!(
+        "{} connection(s) to {dbname} were still open when it was dropped:\n{}",
+        leaks.len(),
+        leaks.iter().map(|leak| format!("  {leak}")).join("\n")
+    );
+    match check {
+        LeakCheck::Off => {}
+        LeakCheck::Warn => eprintln!("{report}"),
+        LeakCheck::Fail if thread::panicking() => eprintln!("{report}"),
+        LeakCheck::Fail => panic!("{report}"),
+    }
+}
@@ -0,0 +1,380 @@
+use anyhow::{Result, bail};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::{Migrate, Migration, Migrator};
+use std::{collections::BTreeMap, fmt};
+
+/// Name of the bookkeeping table sqlx creates, which is never part of a snapshot.
+pub(crate) const MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+
+/// Introspected structure of a database.
+///
+/// Every collection is ordered by name (columns by their position in the table), so
+/// two snapshots of the same schema serialize identically and can be stored as
+/// golden files. The sqlx migrations table and objects owned by extensions are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaSnapshot {
+    /// Tables keyed by name (`schema.table` on PostgreSQL).
+    pub tables: BTreeMap<String, TableSchema>,
+    /// View definitions keyed by view name.
+    pub views: BTreeMap<String, String>,
+    /// Sequences keyed by name. Always empty on MySQL.
+    pub sequences: BTreeMap<String, SequenceSchema>,
+    /// Function and procedure definitions keyed by signature.
+    pub functions: BTreeMap<String, String>,
+    /// Enum labels in declaration order keyed by type name. Always empty on MySQL,
+    /// where enums are part of the column type.
+    pub enums: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableSchema {
+    /// Columns in their physical order.
+    pub columns: Vec<ColumnSchema>,
+    /// Index definitions keyed by index name.
+    pub indexes: BTreeMap<String, String>,
+    /// Constraint definitions keyed by constraint name.
+    pub constraints: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+    pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceSchema {
+    pub data_type: String,
+    pub start: i64,
+    pub increment: i64,
+    pub min: i64,
+    pub max: i64,
+    pub cycle: bool,
+}
+
+/// Differences between two [`SchemaSnapshot`]s, as produced by [`SchemaSnapshot::diff`].
+///
+/// Displays as one block per differing object, with the left side prefixed by `-`
+/// and the right side by `+`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    pub differences: Vec<Difference>,
+}
+
+/// A single object that is missing on one side or defined differently on both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Object path, e.g. `table public.todos column title`.
+    pub path: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+impl SchemaSnapshot {
+    /// Compare this snapshot (left) with another one (right).
+    pub fn diff(&self, other: &SchemaSnapshot) -> SchemaDiff {
+        let mut diff = SchemaDiff::default();
+
+        for (name, left, right) in pair_up(&self.tables, &other.tables) {
+            let path = format!("table {name}");
+            match (left, right) {
+                (Some(left), Some(right)) => {
+                    let left_order: Vec<_> = left.columns.iter().map(|c| &c.name).collect();
+                    let right_order: Vec<_> = right.columns.iter().map(|c| &c.name).collect();
+                    let left_columns = left.columns.iter().map(|c| (&c.name, c)).collect();
+                    let right_columns = right.columns.iter().map(|c| (&c.name, c)).collect();
+                    diff.compare(&path, "column", &left_columns, &right_columns, |c| {
+                        c.render()
+                    });
+                    if left_columns.len() == right_columns.len()
+                        && left_columns.keys().eq(right_columns.keys())
+                        && left_order != right_order
+                    {
+                        diff.push(
+                            format!("{path} column order"),
+                            Some(left_order.iter().join(", ")),
+                            Some(right_order.iter().join(", ")),
+                        );
+                    }
+                    diff.compare(&path, "index", &left.indexes, &right.indexes, String::clone);
+                    diff.compare(
+                        &path,
+                        "constraint",
+                        &left.constraints,
+                        &right.constraints,
+                        String::clone,
+                    );
+                }
+                (left, right) => diff.push(
+                    path,
+                    left.map(TableSchema::render),
+                    right.map(TableSchema::render),
+                ),
+            }
+        }
+        diff.compare("", "view", &self.views, &other.views, String::clone);
+        diff.compare(
+            "",
+            "sequence",
+            &self.sequences,
+            &other.sequences,
+            SequenceSchema::render,
+        );
+        diff.compare(
+            "",
+            "function",
+            &self.functions,
+            &other.functions,
+            String::clone,
+        );
+        diff.compare("", "enum", &self.enums, &other.enums, |labels| {
+            labels.join(", ")
+        });
+
+        diff
+    }
+}
+
+impl TableSchema {
+    fn render(&self) -> String {
+        let mut lines: Vec<String> = self
+            .columns
+            .iter()
+            .map(|c| format!("{} {}", c.name, c.render()))
+            .collect();
+        lines.extend(
+            self.indexes
+                .iter()
+                .map(|(name, def)| format!("index {name}: {def}")),
+        );
+        lines.extend(
+            self.constraints
+                .iter()
+                .map(|(name, def)| format!("constraint {name}: {def}")),
+        );
+        lines.join("\n")
+    }
+}
+
+impl ColumnSchema {
+    fn render(&self) -> String {
+        let mut s = self.data_type.clone();
+        if !self.nullable {
+            s.push_str(" NOT NULL");
+        }
+        if let Some(default) = &self.default {
+            s.push_str(&format!(" DEFAULT {default}"));
+        }
+        s
+    }
+}
+
+impl SequenceSchema {
+    fn render(&self) -> String {
+        format!(
+            "{} START {} INCREMENT {} MINVALUE {} MAXVALUE {}{}",
+            self.data_type,
+            self.start,
+            self.increment,
+            self.min,
+            self.max,
+            if self.cycle { " CYCLE" } else { "" }
+        )
+    }
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    fn push(&mut self, path: String, left: Option<String>, right: Option<String>) {
+        self.differences.push(Difference { path, left, right });
+    }
+
+    fn compare<K, V>(
+        &mut self,
+        parent: &str,
+        kind: &str,
+        left: &BTreeMap<K, V>,
+        right: &BTreeMap<K, V>,
+        render: impl Fn(&V) -> String,
+    ) where
+        K: Ord + fmt::Display,
+        V: PartialEq,
+    {
+        for (name, l, r) in pair_up(left, right) {
+            if l != r {
+                let path = format!("{parent} {kind} {name}").trim_start().to_string();
+                self.push(path, l.map(&render), r.map(&render));
+            }
+        }
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for d in &self.differences {
+            writeln!(f, "{}:", d.path)?;
+            for (sign, side) in [("-", &d.left), ("+", &d.right)] {
+                match side {
+                    Some(def) => {
+                        for line in def.lines() {
+                            writeln!(f, "  {sign} {line}")?;
+                        }
+                    }
+                    None => writeln!(f, "  {sign} (missing)")?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Walks the union of both maps' keys in order.
+fn pair_up<'a, K: Ord, V>(
+    left: &'a BTreeMap<K, V>,
+    right: &'a BTreeMap<K, V>,
+) -> impl Iterator<Item = (&'a K, Option<&'a V>, Option<&'a V>)> {
+    left.keys()
+        .merge(right.keys())
+        .dedup()
+        .map(|k| (k, left.get(k), right.get(k)))
+}
+
+/// A connection whose current schema can be captured as a [`SchemaSnapshot`].
+pub(crate) trait Introspect {
+    async fn schema_snapshot(&mut self) -> Result<SchemaSnapshot>;
+}
+
+/// Applies every migration, reverts them one by one and re-applies them, comparing
+/// the schema after each step with the one recorded on the way up.
+///
+/// `conn` must point to an empty database.
+pub(crate) async fn verify_reversibility<C>(conn: &mut C, m: &Migrator) -> Result<()>
+where
+    C: Migrate + Introspect,
+{
+    let ups: Vec<&Migration> = m
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .collect();
+    let downs: BTreeMap<i64, &Migration> = m
+        .iter()
+        .filter(|m| m.migration_type.is_down_migration())
+        .map(|m| (m.version, m))
+        .collect();
+    if let Some(up) = ups.iter().find(|up| !downs.contains_key(&up.version)) {
+        bail!(
+            "migration {} ({}) has no down script",
+            up.version,
+            up.description
+        );
+    }
+
+    conn.ensure_migrations_table().await?;
+
+    // snapshots[i] is the schema with the first i migrations applied
+    let mut snapshots = vec![conn.schema_snapshot().await?];
+    for up in &ups {
+        conn.apply(up).await?;
+        snapshots.push(conn.schema_snapshot().await?);
+    }
+
+    for (i, up) in ups.iter().enumerate().rev() {
+        conn.revert(downs[&up.version]).await?;
+        let diff = snapshots[i].diff(&conn.schema_snapshot().await?);
+        if !diff.is_empty() {
+            bail!(
+                "down script of migration {} ({}) does not restore the previous schema:\n{diff}",
+                up.version,
+                up.description
+            );
+        }
+    }
+
+    for (i, up) in ups.iter().enumerate() {
+        conn.apply(up).await?;
+        let diff = snapshots[i + 1].diff(&conn.schema_snapshot().await?);
+        if !diff.is_empty() {
+            bail!(
+                "re-applying migration {} ({}) after reverting it produces a different schema:\n{diff}",
+                up.version,
+                up.description
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: &str) -> ColumnSchema {
+        ColumnSchema {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            nullable: false,
+            default: None,
+        }
+    }
+
+    fn snapshot(columns: Vec<ColumnSchema>, indexes: &[(&str, &str)]) -> SchemaSnapshot {
+        let table = TableSchema {
+            columns,
+            indexes: indexes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            constraints: BTreeMap::new(),
+        };
+        SchemaSnapshot {
+            tables: [("public.todos".to_string(), table)].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diff_of_identical_snapshots_should_be_empty() {
+        let s = snapshot(vec![column("id", "integer")], &[("todos_pkey", "pk")]);
+        assert!(s.diff(&s.clone()).is_empty());
+    }
+
+    #[test]
+    fn diff_should_report_changed_columns_and_missing_indexes() {
+        let left = snapshot(
+            vec![column("id", "integer"), column("title", "text")],
+            &[("todos_pkey", "pk"), ("todos_title_idx", "idx")],
+        );
+        let right = snapshot(
+            vec![
+                column("id", "integer"),
+                column("title", "character varying(255)"),
+            ],
+            &[("todos_pkey", "pk")],
+        );
+
+        let diff = left.diff(&right);
+        assert_eq!(diff.differences.len(), 2);
+        assert_eq!(
+            diff.to_string(),
+            "table public.todos column title:\n  - text NOT NULL\n  + character varying(255) NOT NULL\n\
+             table public.todos index todos_title_idx:\n  - idx\n  + (missing)\n"
+        );
+    }
+
+    #[test]
+    fn diff_should_report_column_order() {
+        let left = snapshot(vec![column("id", "integer"), column("title", "text")], &[]);
+        let right = snapshot(vec![column("title", "text"), column("id", "integer")], &[]);
+
+        let diff = left.diff(&right);
+        assert_eq!(diff.differences.len(), 1);
+        assert_eq!(diff.differences[0].path, "table public.todos column order");
+    }
+}
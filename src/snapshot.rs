@@ -0,0 +1,297 @@
+use anyhow::{Context, Result, bail};
+use std::{env, fmt::Write, fs, path::Path};
+
+/// Set this environment variable to write the current contents to the golden files
+/// instead of comparing against them.
+pub const UPDATE_SNAPSHOTS_ENV: &str = "SQLX_DB_TESTER_UPDATE_SNAPSHOTS";
+
+/// How NULL is written in snapshot files, following the `COPY` text convention.
+const NULL: &str = "\\N";
+
+/// Placeholder written instead of the value of a redacted column.
+const REDACTED: &str = "[redacted]";
+
+/// Normalization rules applied to rows before they are compared with a snapshot or
+/// with expected CSV.
+///
+/// # Example
+/// ```
+/// use sqlx_db_tester::SnapshotOptions;
+///
+/// let options = SnapshotOptions::default()
+///     .redact("created_at")
+///     .redact("updated_at");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotOptions {
+    redacted: Vec<String>,
+    ignore_order: bool,
+}
+
+impl SnapshotOptions {
+    /// Replace every non-NULL value of a volatile column (e.g. a `now()` timestamp)
+    /// with a fixed placeholder.
+    pub fn redact(mut self, column: impl Into<String>) -> Self {
+        self.redacted.push(column.into());
+        self
+    }
+
+    /// Sort rows before comparing them, for results without a meaningful order.
+    pub fn ignore_order(mut self) -> Self {
+        self.ignore_order = true;
+        self
+    }
+}
+
+/// Rows read from the database with every value rendered as text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<String>>>,
+}
+
+impl Rows {
+    /// Renders every value as it appears in CSV, applying the normalization rules.
+    fn normalize(&self, options: &SnapshotOptions) -> Vec<Vec<String>> {
+        let redacted: Vec<bool> = self
+            .columns
+            .iter()
+            .map(|c| options.redacted.contains(c))
+            .collect();
+
+        let mut rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(&redacted)
+                    .map(|(v, redact)| match v {
+                        None => NULL.to_string(),
+                        Some(_) if *redact => REDACTED.to_string(),
+                        Some(v) => v.clone(),
+                    })
+                    .collect()
+            })
+            .collect();
+        if options.ignore_order {
+            rows.sort();
+        }
+        rows
+    }
+
+    /// Renders the rows as CSV with a header line.
+    pub(crate) fn to_csv(&self, options: &SnapshotOptions) -> Result<String> {
+        let mut wtr = csv_writer();
+        wtr.write_record(&self.columns)?;
+        for row in self.normalize(options) {
+            wtr.write_record(&row)?;
+        }
+        Ok(String::from_utf8(wtr.into_inner()?)?)
+    }
+}
+
+/// Returns the header of a CSV document.
+pub(crate) fn csv_headers(csv: &str) -> Result<Vec<String>> {
+    let mut rdr = csv::Reader::from_reader(csv.as_bytes());
+    Ok(rdr.headers()?.iter().map(String::from).collect())
+}
+
+/// Compares rows read from the database with expected CSV whose header names the
+/// selected columns. NULL is written as `\N` in the expected CSV.
+pub(crate) fn assert_rows_match(
+    what: &str,
+    rows: &Rows,
+    csv: &str,
+    options: &SnapshotOptions,
+) -> Result<()> {
+    let mut rdr = csv::Reader::from_reader(csv.as_bytes());
+    let mut expected = rdr
+        .records()
+        .map(|r| Ok(r?.iter().map(String::from).collect()))
+        .collect::<Result<Vec<Vec<String>>>>()?;
+    if options.ignore_order {
+        expected.sort();
+    }
+    let actual = rows.normalize(options);
+
+    if expected != actual {
+        bail!(
+            "{what} does not match the expected CSV ({}):\n{}",
+            rows.columns.join(","),
+            row_diff(&expected, &actual, options.ignore_order)?
+        );
+    }
+    Ok(())
+}
+
+fn csv_writer() -> csv::Writer<Vec<u8>> {
+    csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![])
+}
+
+/// Renders a single row as a CSV line.
+fn csv_line(row: &[String]) -> Result<String> {
+    let mut wtr = csv_writer();
+    wtr.write_record(row)?;
+    Ok(String::from_utf8(wtr.into_inner()?)?.trim_end().to_string())
+}
+
+/// Lists differing rows: position by position, or as missing and unexpected rows
+/// when the order is ignored.
+fn row_diff(
+    expected: &[Vec<String>],
+    actual: &[Vec<String>],
+    ignore_order: bool,
+) -> Result<String> {
+    let mut out = String::new();
+    if ignore_order {
+        let mut unexpected = actual.to_vec();
+        let mut missing = vec![];
+        for row in expected {
+            match unexpected.iter().position(|r| r == row) {
+                Some(i) => {
+                    unexpected.remove(i);
+                }
+                None => missing.push(row),
+            }
+        }
+        for row in missing {
+            writeln!(out, "  - {}", csv_line(row)?)?;
+        }
+        for row in &unexpected {
+            writeln!(out, "  + {}", csv_line(row)?)?;
+        }
+        return Ok(out);
+    }
+
+    for i in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(i), actual.get(i));
+        if e == a {
+            continue;
+        }
+        writeln!(out, "row {}:", i + 1)?;
+        if let Some(e) = e {
+            writeln!(out, "  - {}", csv_line(e)?)?;
+        }
+        if let Some(a) = a {
+            writeln!(out, "  + {}", csv_line(a)?)?;
+        }
+    }
+    Ok(out)
+}
+
+/// Compares the rows of `table` with the golden file at `path`, or rewrites the file
+/// when [`UPDATE_SNAPSHOTS_ENV`] is set.
+pub(crate) fn assert_snapshot(
+    table: &str,
+    rows: &Rows,
+    path: &Path,
+    options: &SnapshotOptions,
+) -> Result<()> {
+    let actual = rows.to_csv(options)?;
+
+    if env::var_os(UPDATE_SNAPSHOTS_ENV).is_some() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, actual)?;
+        return Ok(());
+    }
+
+    let expected = fs::read_to_string(path).with_context(|| {
+        format!(
+            "snapshot {} is missing, run with {UPDATE_SNAPSHOTS_ENV}=1 to create it",
+            path.display()
+        )
+    })?;
+    if expected != actual {
+        bail!(
+            "table {table} does not match snapshot {} (run with {UPDATE_SNAPSHOTS_ENV}=1 to update it):\n{}",
+            path.display(),
+            line_diff(&expected, &actual)
+        );
+    }
+    Ok(())
+}
+
+/// Lists the lines that differ between two texts, position by position.
+pub(crate) fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(i), actual.get(i));
+        if e == a {
+            continue;
+        }
+        let _ = writeln!(out, "line {}:", i + 1);
+        if let Some(e) = e {
+            let _ = writeln!(out, "  - {e}");
+        }
+        if let Some(a) = a {
+            let _ = writeln!(out, "  + {a}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Rows {
+        Rows {
+            columns: vec!["id".into(), "title".into(), "created_at".into()],
+            rows: vec![
+                vec![Some("1".into()), Some("a, b".into()), Some("2024".into())],
+                vec![Some("2".into()), None, None],
+            ],
+        }
+    }
+
+    #[test]
+    fn rows_should_render_as_csv() {
+        let options = SnapshotOptions::default().redact("created_at");
+        assert_eq!(
+            rows().to_csv(&options).unwrap(),
+            "id,title,created_at\n1,\"a, b\",[redacted]\n2,\\N,\\N\n"
+        );
+    }
+
+    #[test]
+    fn line_diff_should_show_changed_and_extra_lines() {
+        assert_eq!(
+            line_diff("id\n1\n2\n", "id\n3\n"),
+            "line 2:\n  - 1\n  + 3\nline 3:\n  - 2\n"
+        );
+    }
+
+    #[test]
+    fn rows_should_match_csv_in_order() {
+        let options = SnapshotOptions::default().redact("created_at");
+        let csv = "id,title,created_at\n1,\"a, b\",[redacted]\n2,\\N,\\N\n";
+        assert_rows_match("todos", &rows(), csv, &options).unwrap();
+
+        let csv = "id,title,created_at\n1,\"a, b\",[redacted]\n2,c,\\N\n";
+        let err = assert_rows_match("todos", &rows(), csv, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "todos does not match the expected CSV (id,title,created_at):\nrow 2:\n  - 2,c,\\N\n  + 2,\\N,\\N\n"
+        );
+    }
+
+    #[test]
+    fn rows_should_match_csv_ignoring_order() {
+        let options = SnapshotOptions::default().ignore_order();
+        let rows = Rows {
+            columns: vec!["title".into()],
+            rows: vec![vec![Some("b".into())], vec![Some("a".into())]],
+        };
+        assert_rows_match("query", &rows, "title\na\nb\n", &options).unwrap();
+
+        let err = assert_rows_match("query", &rows, "title\na\nc\n", &options).unwrap_err();
+        assert!(err.to_string().ends_with(":\n  - c\n  + b\n"), "{err}");
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::{Result, bail};
+use itertools::Itertools;
+use std::{
+    cell::Cell,
+    fmt,
+    future::{Future, poll_fn},
+    pin::pin,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tracing::{
+    Dispatch, Event, Metadata, Subscriber,
+    dispatcher::{self, DefaultGuard},
+    field::{Field, Visit},
+    subscriber::Interest,
+};
+use tracing_core::span;
+
+/// Target of the events sqlx emits for every executed statement.
+const QUERY_TARGET: &str = "sqlx::query";
+
+thread_local! {
+    /// Set while the tester runs statements on its own behalf.
+    static UNRECORDED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A statement executed while a [`StatementLog`] was recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub sql: String,
+    /// Number of bind parameters referenced by the SQL text.
+    pub params: usize,
+    pub duration: Duration,
+    pub rows_affected: u64,
+    pub rows_returned: u64,
+}
+
+/// Records the statements executed on the current thread, as returned by
+/// `record_statements` on a tester.
+///
+/// sqlx reports statements through `tracing`, so the log sets a subscriber as the
+/// current thread's default while it's alive, passing every event on to the
+/// subscriber that was in place before. That covers statements awaited by the test
+/// itself, on any runtime flavor; to record tasks spawned onto other worker threads
+/// too, spawn them with `.with_current_subscriber()` from
+/// `tracing::instrument::WithSubscriber`.
+///
+/// The log takes every statement sqlx runs on the thread, through any pool or
+/// connection, as sqlx doesn't report which one ran it. Only the statements the
+/// tester runs on its own behalf are left out: its connection hooks (settings,
+/// `SET ROLE` and `set_config` of `pool_as` and friends, the frozen clock) and the
+/// triggers of `capture_changes`.
+///
+/// If the thread panics while the log is alive (e.g. a failing assertion), the
+/// recorded statements are printed to stderr.
+pub struct StatementLog {
+    statements: Arc<Mutex<Vec<Statement>>>,
+    _default: DefaultGuard,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Placeholder {
+    /// PostgreSQL-style `$1`, `$2`, ...
+    #[cfg(feature = "postgres")]
+    Dollar,
+    /// MySQL-style `?`
+    #[cfg(feature = "mysql")]
+    Question,
+}
+
+impl StatementLog {
+    pub(crate) fn start(placeholder: Placeholder) -> Self {
+        let statements = Arc::new(Mutex::new(vec![]));
+        let subscriber = StatementSubscriber {
+            placeholder,
+            statements: statements.clone(),
+            inner: dispatcher::get_default(Dispatch::clone),
+        };
+        let default = dispatcher::set_default(&Dispatch::new(subscriber));
+        Self {
+            statements,
+            _default: default,
+        }
+    }
+
+    /// The statements recorded so far, in execution order.
+    pub fn statements(&self) -> Vec<Statement> {
+        self.statements.lock().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget the statements recorded so far.
+    pub fn clear(&self) {
+        self.statements.lock().unwrap().clear();
+    }
+
+    /// Assert that exactly `expected` statements were executed.
+    pub fn assert_count(&self, expected: usize) -> Result<()> {
+        let count = self.len();
+        if count != expected {
+            bail!("expected {expected} statements, {count} were executed:\n{self}");
+        }
+        Ok(())
+    }
+
+    /// Assert that no SQL text was executed more than `max` times, which catches
+    /// N+1 patterns issuing the same query once per row.
+    pub fn assert_no_repeats(&self, max: usize) -> Result<()> {
+        let statements = self.statements();
+        let repeated: Vec<_> = statements
+            .iter()
+            .counts_by(|s| s.sql.as_str())
+            .into_iter()
+            .filter(|(_, n)| *n > max)
+            .sorted()
+            .collect();
+        if !repeated.is_empty() {
+            bail!(
+                "statements executed more than {max} times:\n{}\n\n{self}",
+                repeated
+                    .iter()
+                    .map(|(sql, n)| format!("  {n}x {sql}"))
+                    .join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for StatementLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, s) in self.statements().iter().enumerate() {
+            writeln!(
+                f,
+                "{:>4}. [{:?}, {} params, {} affected, {} returned] {}",
+                i + 1,
+                s.duration,
+                s.params,
+                s.rows_affected,
+                s.rows_returned,
+                s.sql
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StatementLog {
+    fn drop(&mut self) {
+        if thread::panicking() && !self.is_empty() {
+            eprintln!("statements executed before the panic:\n{self}");
+        }
+    }
+}
+
+/// Runs `fut` without recording its statements in the statement log of the thread
+/// polling it.
+pub(crate) async fn unrecorded<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    poll_fn(|cx| {
+        let outer = UNRECORDED.replace(true);
+        let poll = fut.as_mut().poll(cx);
+        UNRECORDED.set(outer);
+        poll
+    })
+    .await
+}
+
+/// Counts the bind parameters referenced outside of quoted strings and identifiers.
+pub(crate) fn count_params(sql: &str, placeholder: Placeholder) -> usize {
+    let mut count = 0;
+    let mut quote = None;
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            #[cfg(feature = "mysql")]
+            (None, '?') if matches!(placeholder, Placeholder::Question) => count += 1,
+            #[cfg(feature = "postgres")]
+            (None, '$') if matches!(placeholder, Placeholder::Dollar) => {
+                let end = rest
+                    .find(|d: char| !d.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                count = count.max(rest[..end].parse().unwrap_or(0));
+                rest = &rest[end..];
+            }
+            _ => {}
+        }
+    }
+    count
+}
+
+/// Records sqlx's statement events into a log, and passes every event and span on
+/// to the subscriber that was the default before recording started.
+struct StatementSubscriber {
+    placeholder: Placeholder,
+    statements: Arc<Mutex<Vec<Statement>>>,
+    inner: Dispatch,
+}
+
+#[derive(Default)]
+struct StatementVisitor {
+    summary: String,
+    statement: String,
+    rows_affected: u64,
+    rows_returned: u64,
+    elapsed_secs: f64,
+}
+
+impl Visit for StatementVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "summary" => self.summary = value.to_string(),
+            "db.statement" => self.statement = value.to_string(),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "rows_affected" => self.rows_affected = value,
+            "rows_returned" => self.rows_returned = value,
+            _ => {}
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed_secs = value;
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+impl Subscriber for StatementSubscriber {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.target() == QUERY_TARGET {
+            Interest::always()
+        } else {
+            self.inner.register_callsite(metadata)
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == QUERY_TARGET || self.inner.enabled(metadata)
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        self.inner.new_span(span)
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        self.inner.record(span, values);
+    }
+
+    fn record_follows_from(&self, span: &span::Id, follows: &span::Id) {
+        self.inner.record_follows_from(span, follows);
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        if event.metadata().target() == QUERY_TARGET && !UNRECORDED.get() {
+            let mut visitor = StatementVisitor::default();
+            event.record(&mut visitor);
+            // sqlx only sends the full statement when it's longer than the summary
+            let sql = match visitor.statement.trim() {
+                "" => visitor.summary,
+                sql => sql.to_string(),
+            };
+            self.statements.lock().unwrap().push(Statement {
+                params: count_params(&sql, self.placeholder),
+                sql,
+                duration: Duration::from_secs_f64(visitor.elapsed_secs),
+                rows_affected: visitor.rows_affected,
+                rows_returned: visitor.rows_returned,
+            });
+        }
+        if self.inner.enabled(event.metadata()) {
+            self.inner.event(event);
+        }
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.inner.enter(span);
+    }
+
+    fn exit(&self, span: &span::Id) {
+        self.inner.exit(span);
+    }
+
+    fn clone_span(&self, id: &span::Id) -> span::Id {
+        self.inner.clone_span(id)
+    }
+
+    fn try_close(&self, id: span::Id) -> bool {
+        self.inner.try_close(id)
+    }
+
+    fn current_span(&self) -> span::Current {
+        self.inner.current_span()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "postgres")]
+    fn count_params_should_skip_quoted_dollar_params() {
+        let sql = "SELECT $1, '$5', \"a$3\", ? FROM t WHERE id = $2";
+        assert_eq!(count_params(sql, Placeholder::Dollar), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "mysql")]
+    fn count_params_should_skip_quoted_question_marks() {
+        let sql = "SELECT ?, '?', $1 FROM t WHERE id = ? AND `a?` = 1";
+        assert_eq!(count_params(sql, Placeholder::Question), 2);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::naming::current_test;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{env, fs, process, time::Duration};
+
+/// Start of the comment PostgreSQL test databases are tagged with. Templates are
+/// commented `sqlx-db-tester template ...` instead, so they're never taken for test
+/// databases.
+#[cfg(feature = "postgres")]
+pub(crate) const COMMENT_PREFIX: &str = "sqlx-db-tester test ";
+
+/// Who created a test database, recorded with it so leftovers can be cleaned up
+/// without dropping the databases of tests that are still running.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DbTag {
+    pub host: String,
+    pub pid: u32,
+    /// Tells apart boots of the host, and machines or VMs sharing a hostname.
+    #[serde(default)]
+    pub boot_id: Option<String>,
+    /// Tells apart containers sharing the host's network, and so its hostname, but
+    /// not its process ids.
+    #[serde(default)]
+    pub pid_ns: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub version: String,
+    pub test: Option<String>,
+}
+
+impl DbTag {
+    /// A tag for a database created now by the current process and test.
+    pub fn current() -> Self {
+        Self {
+            host: hostname(),
+            pid: process::id(),
+            boot_id: boot_id(),
+            pid_ns: pid_namespace(),
+            created_at: Utc::now(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            test: current_test(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("tags always serialize")
+    }
+
+    pub fn from_json(json: &str) -> Option<Self> {
+        serde_json::from_str(json).ok()
+    }
+
+    #[cfg(feature = "postgres")]
+    pub fn to_comment(&self) -> String {
+        format!("{COMMENT_PREFIX}{}", self.to_json())
+    }
+
+    #[cfg(feature = "postgres")]
+    pub fn from_comment(comment: &str) -> Option<Self> {
+        Self::from_json(comment.strip_prefix(COMMENT_PREFIX)?)
+    }
+
+    /// Whether the database can be dropped: it's older than `ttl`, or it was created
+    /// by a process that's gone. The process is only looked up when it ran on this
+    /// host, during this boot and in this PID namespace; anything that can't be
+    /// verified, such as a database created in another container or by an older
+    /// version, is assumed to be in use and only expires with the TTL.
+    pub fn is_stale(&self, ttl: Option<Duration>, now: DateTime<Utc>) -> bool {
+        let expired =
+            ttl.is_some_and(|ttl| (now - self.created_at).to_std().is_ok_and(|age| age > ttl));
+        expired || (self.is_local() && !process_alive(self.pid))
+    }
+
+    /// Whether the process that created the database shares this process's id space.
+    fn is_local(&self) -> bool {
+        let same =
+            |theirs: &Option<String>, ours: Option<String>| theirs.is_some() && *theirs == ours;
+        self.host == hostname()
+            && same(&self.boot_id, boot_id())
+            && same(&self.pid_ns, pid_namespace())
+    }
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The id the Linux kernel picked for the current boot.
+fn boot_id() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+}
+
+/// The PID namespace of the current process, e.g. `pid:[4026531836]`.
+fn pid_namespace() -> Option<String> {
+    fs::read_link("/proc/self/ns/pid")
+        .ok()
+        .map(|ns| ns.to_string_lossy().into_owned())
+}
+
+/// Whether a process with the given id is running on this host. Only Linux can
+/// tell; elsewhere every process is assumed to be alive.
+fn process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        fs::exists(format!("/proc/{pid}")).unwrap_or(true)
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn tag_should_round_trip_through_a_comment() {
+        let tag = std::thread::Builder::new()
+            .name("api::tests::creates_user".to_string())
+            .spawn(DbTag::current)
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(tag.pid, process::id());
+        assert_eq!(tag.test.as_deref(), Some("api::tests::creates_user"));
+        assert_eq!(DbTag::from_comment(&tag.to_comment()), Some(tag));
+        assert_eq!(
+            DbTag::from_comment("sqlx-db-tester template 0123456789abcdef"),
+            None
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tag_should_be_stale_once_its_process_is_gone() {
+        use std::process::Command;
+
+        let now = Utc::now();
+        let tag = DbTag::current();
+        assert!(!tag.is_stale(None, now));
+
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let dead = DbTag {
+            pid: child.id(),
+            ..tag.clone()
+        };
+        assert!(dead.is_stale(None, now));
+        // databases of other hosts, boots and containers are left alone
+        let remote = DbTag {
+            host: "elsewhere".to_string(),
+            ..dead.clone()
+        };
+        assert!(!remote.is_stale(None, now));
+        let rebooted = DbTag {
+            boot_id: Some("00000000-0000-0000-0000-000000000000".to_string()),
+            ..dead.clone()
+        };
+        assert!(!rebooted.is_stale(None, now));
+        let container = DbTag {
+            pid_ns: Some("pid:[1]".to_string()),
+            ..dead.clone()
+        };
+        assert!(!container.is_stale(None, now));
+    }
+
+    #[test]
+    fn tag_without_host_identity_should_be_kept() {
+        // written by a version that didn't record the boot and the PID namespace
+        let json = r#"{"host":"h","pid":1,"created_at":"2024-01-01T00:00:00Z","version":"0.1.0","test":null}"#;
+        let tag = DbTag {
+            host: hostname(),
+            pid: u32::MAX,
+            ..DbTag::from_json(json).unwrap()
+        };
+        assert_eq!(
+            (tag.boot_id.as_deref(), tag.pid_ns.as_deref()),
+            (None, None)
+        );
+        assert!(!tag.is_stale(None, Utc::now()));
+    }
+
+    #[test]
+    fn tag_should_be_stale_once_its_ttl_expired() {
+        let tag = DbTag {
+            host: "elsewhere".to_string(),
+            ..DbTag::current()
+        };
+        let later = tag.created_at + TimeDelta::minutes(10);
+        assert!(!tag.is_stale(Some(Duration::from_secs(3600)), later));
+        assert!(tag.is_stale(Some(Duration::from_secs(60)), later));
+    }
+}